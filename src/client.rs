@@ -1,10 +1,14 @@
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
 
-use tokio::sync::{mpsc, Mutex, RwLock};
+use rand::Rng;
+use tokio::sync::{Mutex, Notify, RwLock};
 
 use crate::prelude::*;
+use crate::store::{MessageKind, MessageStore};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ClientConfig {
     pub inner: seabird::ClientConfig,
     pub tag: String,
@@ -19,122 +23,792 @@ impl ClientConfig {
     }
 }
 
+// How many outgoing messages we'll hold for a single target before we start
+// dropping the oldest ones. Targets are independent, so a flooded or slow
+// target can never stall delivery to any other target.
+const TARGET_QUEUE_CAPACITY: usize = 100;
+
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimitConfig {
+    pub burst: f64,
+    pub refill_per_sec: f64,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            burst: 5.0,
+            refill_per_sec: 1.0,
+        }
+    }
+}
+
+// A simple token bucket: a message may send immediately if a token is
+// available, otherwise it waits for the bucket to refill.
+struct TokenBucket {
+    config: RateLimitConfig,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(config: RateLimitConfig) -> Self {
+        TokenBucket {
+            tokens: config.burst,
+            config,
+            last_refill: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.config.refill_per_sec).min(self.config.burst);
+        self.last_refill = now;
+    }
+
+    async fn acquire(&mut self) {
+        loop {
+            self.refill();
+
+            if self.tokens >= 1.0 {
+                self.tokens -= 1.0;
+                return;
+            }
+
+            let deficit = 1.0 - self.tokens;
+            tokio::time::sleep(Duration::from_secs_f64(deficit / self.config.refill_per_sec)).await;
+        }
+    }
+}
+
 #[derive(Debug)]
 enum OutgoingMessage {
-    Action(proto::PerformActionRequest),
-    Message(proto::SendMessageRequest),
+    Action {
+        seq: u64,
+        request: proto::PerformActionRequest,
+    },
+    Message {
+        seq: u64,
+        request: proto::SendMessageRequest,
+    },
+}
+
+impl OutgoingMessage {
+    fn seq(&self) -> u64 {
+        match self {
+            OutgoingMessage::Action { seq, .. } => *seq,
+            OutgoingMessage::Message { seq, .. } => *seq,
+        }
+    }
+}
+
+// A bounded, drop-oldest queue of messages bound for a single target channel.
+struct TargetQueue {
+    backend_tag: String,
+    channel_id: String,
+    inner: Mutex<VecDeque<OutgoingMessage>>,
+    notify: Notify,
+    dropped: AtomicU64,
+}
+
+impl TargetQueue {
+    fn new(backend_tag: String, channel_id: String) -> Self {
+        TargetQueue {
+            backend_tag,
+            channel_id,
+            inner: Mutex::new(VecDeque::with_capacity(TARGET_QUEUE_CAPACITY)),
+            notify: Notify::new(),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    // Seqs currently sitting un-popped in the queue, so a backlog replay can
+    // skip messages that are already waiting here instead of double-queueing
+    // them. Deliberately NOT a high-water mark: a message that was popped and
+    // failed to send must still be eligible for replay.
+    async fn enqueued_seqs(&self) -> HashSet<u64> {
+        self.inner
+            .lock()
+            .await
+            .iter()
+            .map(OutgoingMessage::seq)
+            .collect()
+    }
+
+    async fn push(&self, msg: OutgoingMessage) {
+        let mut guard = self.inner.lock().await;
+
+        if guard.len() >= TARGET_QUEUE_CAPACITY {
+            guard.pop_front();
+            let total_dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+            warn!(
+                "spool for {}:{} is full, dropped oldest message ({} dropped total)",
+                self.backend_tag, self.channel_id, total_dropped
+            );
+        }
+
+        guard.push_back(msg);
+        drop(guard);
+
+        self.notify.notify_one();
+    }
+
+    async fn pop(&self) -> OutgoingMessage {
+        loop {
+            if let Some(msg) = self.inner.lock().await.pop_front() {
+                return msg;
+            }
+
+            self.notify.notified().await;
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct ChannelTarget {
+    backend_tag: String,
     id: String,
     user_prefix: Option<String>,
     user_suffix: Option<String>,
+    rate_limit: RateLimitConfig,
+    message_format: Option<String>,
+    action_format: Option<String>,
+    mention_format: Option<String>,
+    command_format: Option<String>,
 }
 
 impl ChannelTarget {
-    pub fn new(id: String, user_prefix: Option<String>, user_suffix: Option<String>) -> Self {
+    pub fn new(
+        backend_tag: String,
+        id: String,
+        user_prefix: Option<String>,
+        user_suffix: Option<String>,
+    ) -> Self {
         ChannelTarget {
+            backend_tag,
             id,
             user_prefix,
             user_suffix,
+            rate_limit: RateLimitConfig::default(),
+            message_format: None,
+            action_format: None,
+            mention_format: None,
+            command_format: None,
         }
     }
+
+    pub fn with_rate_limit(mut self, rate_limit: RateLimitConfig) -> Self {
+        self.rate_limit = rate_limit;
+        self
+    }
+
+    pub fn with_message_format(mut self, format: Option<String>) -> Self {
+        self.message_format = format;
+        self
+    }
+
+    pub fn with_action_format(mut self, format: Option<String>) -> Self {
+        self.action_format = format;
+        self
+    }
+
+    pub fn with_mention_format(mut self, format: Option<String>) -> Self {
+        self.mention_format = format;
+        self
+    }
+
+    pub fn with_command_format(mut self, format: Option<String>) -> Self {
+        self.command_format = format;
+        self
+    }
+
+    // `{user}` is the already prefix/suffix-wrapped display name; `{text}`,
+    // `{nick}`, and `{command}` are filled in depending on the event kind.
+    fn message_format(&self) -> &str {
+        self.message_format.as_deref().unwrap_or("{user}: {text}")
+    }
+
+    fn action_format(&self) -> &str {
+        self.action_format.as_deref().unwrap_or("* {user} {text}")
+    }
+
+    fn mention_format(&self) -> &str {
+        self.mention_format
+            .as_deref()
+            .unwrap_or("{user}: {nick}: {text}")
+    }
+
+    fn command_format(&self) -> &str {
+        self.command_format
+            .as_deref()
+            .unwrap_or("{user}: !{command} {text}")
+    }
+
+    fn display_name(&self, user_display_name: &str) -> String {
+        format!(
+            "{}{}{}",
+            self.user_prefix.as_deref().unwrap_or(""),
+            user_display_name,
+            self.user_suffix.as_deref().unwrap_or(""),
+        )
+    }
 }
 
-// Client represents the running proxy
+// Fills in `{placeholder}` tokens in a per-target message template with a
+// single left-to-right scan. This deliberately does NOT do a sequential
+// whole-string `.replace()` per placeholder: a value (e.g. a remote user's
+// attacker-controlled display name) can itself contain `{text}`-shaped text,
+// and re-scanning already-substituted output for later placeholders would
+// let that value corrupt or spoof the rest of the rendered message.
+fn render_template(template: &str, vars: &[(&str, &str)]) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+
+        let after_brace = &rest[start + 1..];
+        match after_brace.find('}') {
+            Some(end) => {
+                let key = &after_brace[..end];
+
+                match vars.iter().find(|(k, _)| *k == key) {
+                    Some((_, value)) => out.push_str(value),
+                    None => out.push_str(&rest[start..start + 1 + end + 1]),
+                }
+
+                rest = &after_brace[end + 1..];
+            }
+            None => {
+                out.push_str(&rest[start..]);
+                rest = "";
+                break;
+            }
+        }
+    }
+
+    out.push_str(rest);
+    out
+}
+
+// A single backend connection. Each backend is a distinct Seabird server and
+// is identified by its own tag, so that an event sourced from one backend can
+// be proxied onto channels living on any other backend.
+#[derive(Debug)]
+struct Backend {
+    tag: String,
+    config: seabird::ClientConfig,
+    // A `RwLock` rather than a `Mutex`: the underlying seabird::Client wraps
+    // a tonic channel, which is cheap to clone and safe to use concurrently,
+    // so spool tasks only take a brief read lock to clone out a handle
+    // rather than holding a lock across the send RPC itself. The write lock
+    // is only taken on reconnect, to swap in a freshly connected client.
+    inner: RwLock<seabird::Client>,
+}
+
+// How long a reconnect loop will wait before retrying, growing exponentially
+// (full-jitter) up to a cap so a backend that's down for a while doesn't get
+// hammered with reconnect attempts.
+const RECONNECT_BASE_DELAY: Duration = Duration::from_millis(500);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(30);
+
+// A connection that's stayed up at least this long is considered healthy
+// again, so a later drop starts backing off from scratch instead of
+// compounding on top of earlier failures.
+const HEALTHY_CONNECTION_THRESHOLD: Duration = Duration::from_secs(60);
+
+struct Backoff {
+    attempt: u32,
+}
+
+impl Backoff {
+    fn new() -> Self {
+        Backoff { attempt: 0 }
+    }
+
+    fn reset(&mut self) {
+        self.attempt = 0;
+    }
+
+    // Exponential backoff with full jitter: sleeps a random duration between
+    // zero and `min(cap, base * 2^attempt)`.
+    async fn wait(&mut self) {
+        let exp = RECONNECT_BASE_DELAY.saturating_mul(1 << self.attempt.min(6));
+        let capped = exp.min(RECONNECT_MAX_DELAY);
+        self.attempt += 1;
+
+        let jittered_ms = rand::thread_rng().gen_range(0..=capped.as_millis() as u64);
+        tokio::time::sleep(Duration::from_millis(jittered_ms)).await;
+    }
+}
+
+// Names and help text for the management commands registered with each
+// backend, so they show up in that backend's native help/command listing.
+fn management_commands() -> HashMap<String, proto::CommandMetadata> {
+    let mut commands = HashMap::new();
+
+    commands.insert(
+        "proxy-list".to_string(),
+        proto::CommandMetadata {
+            short_help: "list all proxied channels".to_string(),
+            full_help: "proxy-list - list every proxied source channel and its targets, on all backends".to_string(),
+        },
+    );
+    commands.insert(
+        "proxy-add".to_string(),
+        proto::CommandMetadata {
+            short_help: "add a proxy target".to_string(),
+            full_help:
+                "proxy-add <source> <target> - proxy messages from <source> to <target>, each as [backend_tag:]channel_id"
+                    .to_string(),
+        },
+    );
+    commands.insert(
+        "proxy-remove".to_string(),
+        proto::CommandMetadata {
+            short_help: "remove a proxy target".to_string(),
+            full_help:
+                "proxy-remove <source> <target> - stop proxying messages from <source> to <target>"
+                    .to_string(),
+        },
+    );
+    commands.insert(
+        "proxy-status".to_string(),
+        proto::CommandMetadata {
+            short_help: "show proxy backend status".to_string(),
+            full_help: "proxy-status - show the configured backends and proxy mapping counts"
+                .to_string(),
+        },
+    );
+
+    commands
+}
+
+// Parses a "[backend_tag:]channel_id" reference as used by the proxy-add and
+// proxy-remove commands, defaulting to the backend the command was issued on.
+fn parse_channel_ref(default_backend: &str, s: &str) -> (String, String) {
+    match s.split_once(':') {
+        Some((backend, channel)) => (backend.to_string(), channel.to_string()),
+        None => (default_backend.to_string(), s.to_string()),
+    }
+}
+
+// Client represents the running proxy, bridging events between any number of
+// backend Seabird connections.
 #[derive(Debug)]
 pub struct Client {
-    config: ClientConfig,
-    inner: Mutex<seabird::Client>,
-    proxied_channels: RwLock<BTreeMap<String, Vec<ChannelTarget>>>,
+    backends: BTreeMap<String, Arc<Backend>>,
+    // Keyed by (source backend tag, source channel id).
+    proxied_channels: RwLock<BTreeMap<(String, String), Vec<ChannelTarget>>>,
+    // User ids allowed to run the proxy-* management commands.
+    admins: RwLock<HashSet<String>>,
+    // One spool per destination (backend tag, channel id), each drained by
+    // its own task so a slow or flooded target can't stall delivery to
+    // anything else.
+    target_queues: RwLock<HashMap<(String, String), Arc<TargetQueue>>>,
+    // Durable record of proxied messages, used to replay anything a target's
+    // spool never confirmed delivering before it was last torn down.
+    store: Arc<dyn MessageStore>,
 }
 
 impl Client {
-    pub async fn new(config: ClientConfig) -> Result<Arc<Self>> {
-        let seabird_client = seabird::Client::new(config.inner.clone()).await?;
+    pub async fn new(configs: Vec<ClientConfig>, store: Arc<dyn MessageStore>) -> Result<Arc<Self>> {
+        if configs.is_empty() {
+            return Err(format_err!("at least one backend must be configured"));
+        }
+
+        let mut backends = BTreeMap::new();
+
+        for config in configs {
+            let seabird_client = seabird::Client::new(config.inner.clone()).await?;
+
+            backends.insert(
+                config.tag.clone(),
+                Arc::new(Backend {
+                    tag: config.tag,
+                    config: config.inner,
+                    inner: RwLock::new(seabird_client),
+                }),
+            );
+        }
 
         Ok(Arc::new(Client {
-            config,
-            inner: Mutex::new(seabird_client),
+            backends,
             proxied_channels: RwLock::new(BTreeMap::new()),
+            admins: RwLock::new(HashSet::new()),
+            target_queues: RwLock::new(HashMap::new()),
+            store,
         }))
     }
 
     pub async fn set_proxied_channels(
         &self,
-        proxied_channels: BTreeMap<String, Vec<ChannelTarget>>,
+        proxied_channels: BTreeMap<(String, String), Vec<ChannelTarget>>,
     ) {
         let mut guard = self.proxied_channels.write().await;
         *guard = proxied_channels
     }
 
+    pub async fn set_admins(&self, admins: HashSet<String>) {
+        let mut guard = self.admins.write().await;
+        *guard = admins
+    }
+
+    // Starts a spool (and replays any un-delivered backlog from the store)
+    // for every currently configured target, so a target doesn't have to
+    // wait for fresh traffic before it catches up on what it missed.
+    pub async fn backfill_all(&self) -> Result<()> {
+        let targets: Vec<((String, String), RateLimitConfig)> = self
+            .proxied_channels
+            .read()
+            .await
+            .values()
+            .flatten()
+            .map(|target| {
+                (
+                    (target.backend_tag.clone(), target.id.clone()),
+                    target.rate_limit,
+                )
+            })
+            .collect();
+
+        for (key, rate_limit) in targets {
+            self.get_or_spawn_spool(key, rate_limit).await?;
+        }
+
+        Ok(())
+    }
+
+    // Queues a message to a channel on a specific backend, going through the
+    // same per-target spool (and rate limiting) as messages proxied from
+    // other backends' events.
+    pub(crate) async fn queue_message(
+        &self,
+        backend_tag: &str,
+        channel_id: String,
+        text: String,
+    ) -> Result<()> {
+        self.enqueue(
+            backend_tag,
+            channel_id,
+            RateLimitConfig::default(),
+            MessageKind::Message,
+            text,
+            HashMap::new(),
+        )
+        .await
+    }
+
     pub async fn run(&self) -> Result<()> {
-        // We want a fairly large queue because these messages are small and
-        // sometimes we'll be proxying to multiple channels.
-        let (writer, reader) = mpsc::channel(100);
-        futures::future::try_join(self.run_reader(writer), self.run_writer(reader)).await?;
+        futures::future::join_all(
+            self.backends
+                .keys()
+                .map(|tag| self.run_supervised_reader(tag)),
+        )
+        .await;
 
-        Err(format_err!("run exited early"))
+        Err(format_err!("all backend readers exited"))
     }
 }
 
 impl Client {
-    async fn run_reader(&self, mut queue: mpsc::Sender<OutgoingMessage>) -> Result<()> {
-        debug!("Getting stream");
+    // Records a message in the store, then looks up (creating if necessary)
+    // the spool task for its target and pushes it on. Spool tasks live for
+    // as long as the process does once created, since targets can come and
+    // go via proxy-add/remove.
+    async fn enqueue(
+        &self,
+        backend_tag: &str,
+        channel_id: String,
+        rate_limit: RateLimitConfig,
+        kind: MessageKind,
+        text: String,
+        tags: HashMap<String, String>,
+    ) -> Result<()> {
+        // Spawn (and backlog-replay) the spool *before* recording this
+        // message, so a brand-new target's replay can never see — and thus
+        // never double-queue — the message we're about to push below.
+        let key = (backend_tag.to_string(), channel_id);
+        let queue = self.get_or_spawn_spool(key.clone(), rate_limit).await?;
 
-        let mut stream = self
-            .inner
-            .lock()
+        let seq = self
+            .store
+            .record(&key.0, &key.1, kind, &text, &tags)
+            .await?;
+
+        let msg = match kind {
+            MessageKind::Message => OutgoingMessage::Message {
+                seq,
+                request: proto::SendMessageRequest {
+                    channel_id: key.1,
+                    text,
+                    tags,
+                },
+            },
+            MessageKind::Action => OutgoingMessage::Action {
+                seq,
+                request: proto::PerformActionRequest {
+                    channel_id: key.1,
+                    text,
+                    tags,
+                },
+            },
+        };
+
+        queue.push(msg).await;
+
+        Ok(())
+    }
+
+    async fn get_or_spawn_spool(
+        &self,
+        key: (String, String),
+        rate_limit: RateLimitConfig,
+    ) -> Result<Arc<TargetQueue>> {
+        if let Some(queue) = self.target_queues.read().await.get(&key) {
+            return Ok(queue.clone());
+        }
+
+        let backend = self
+            .backends
+            .get(&key.0)
+            .ok_or_else(|| format_err!("unknown backend tag {}", key.0))?
+            .clone();
+
+        let mut guard = self.target_queues.write().await;
+
+        // Someone may have spawned this spool while we were waiting for the
+        // write lock.
+        if let Some(queue) = guard.get(&key) {
+            return Ok(queue.clone());
+        }
+
+        let queue = Arc::new(TargetQueue::new(key.0.clone(), key.1.clone()));
+        guard.insert(key.clone(), queue.clone());
+        drop(guard);
+
+        self.replay_backlog(&key, &queue).await?;
+
+        tokio::spawn(run_target_spool(
+            backend,
+            queue.clone(),
+            rate_limit,
+            self.store.clone(),
+        ));
+
+        Ok(queue)
+    }
+
+    // Pushes any of a target's stored-but-undelivered messages onto its
+    // spool that aren't already sitting there. Used both when a spool is
+    // first created and after a backend reconnects, since a spool's
+    // in-memory queue only ever holds messages it hasn't yet attempted to
+    // send — anything it popped and failed to deliver during an outage only
+    // survives in the store.
+    async fn replay_backlog(&self, key: &(String, String), queue: &Arc<TargetQueue>) -> Result<()> {
+        let already_enqueued = queue.enqueued_seqs().await;
+
+        for stored in self.store.backlog(&key.0, &key.1).await? {
+            if already_enqueued.contains(&stored.seq) {
+                continue;
+            }
+
+            let msg = match stored.kind {
+                MessageKind::Message => OutgoingMessage::Message {
+                    seq: stored.seq,
+                    request: proto::SendMessageRequest {
+                        channel_id: key.1.clone(),
+                        text: stored.text,
+                        tags: stored.tags,
+                    },
+                },
+                MessageKind::Action => OutgoingMessage::Action {
+                    seq: stored.seq,
+                    request: proto::PerformActionRequest {
+                        channel_id: key.1.clone(),
+                        text: stored.text,
+                        tags: stored.tags,
+                    },
+                },
+            };
+
+            queue.push(msg).await;
+        }
+
+        Ok(())
+    }
+
+    // Replays backlog for every target currently spooled on a backend. Called
+    // after that backend reconnects, so a transient disconnect doesn't leave
+    // a gap of messages that were recorded but never confirmed delivered.
+    async fn backfill_backend(&self, tag: &str) -> Result<()> {
+        let targets: Vec<(String, String)> = self
+            .target_queues
+            .read()
             .await
+            .keys()
+            .filter(|key| key.0 == tag)
+            .cloned()
+            .collect();
+
+        for key in targets {
+            let queue = self.target_queues.read().await.get(&key).cloned();
+
+            if let Some(queue) = queue {
+                self.replay_backlog(&key, &queue).await?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Drains a single target's spool, waiting for a rate-limit token before each
+// send so a single destination can't be flooded, and marking each message
+// delivered in the store once it's been sent.
+async fn run_target_spool(
+    backend: Arc<Backend>,
+    queue: Arc<TargetQueue>,
+    rate_limit: RateLimitConfig,
+    store: Arc<dyn MessageStore>,
+) {
+    let mut bucket = TokenBucket::new(rate_limit);
+
+    loop {
+        let msg = queue.pop().await;
+        bucket.acquire().await;
+
+        // Clone the client handle out under a brief read lock rather than
+        // holding a lock for the duration of the send, so a hung or slow RPC
+        // to this target can't stall every other target's spool on the same
+        // backend.
+        let mut client = backend.inner.read().await.clone();
+
+        let (seq, result) = match msg {
+            OutgoingMessage::Action { seq, request } => {
+                debug!(
+                    "Performing action {} on {} ({})",
+                    request.text, request.channel_id, backend.tag
+                );
+                let result = client
+                    .perform_action(request.channel_id, request.text, None)
+                    .await;
+                (seq, result)
+            }
+            OutgoingMessage::Message { seq, request } => {
+                debug!(
+                    "Sending message {} to {} ({})",
+                    request.text, request.channel_id, backend.tag
+                );
+                let result = client
+                    .send_message(request.channel_id, request.text, None)
+                    .await;
+                (seq, result)
+            }
+        };
+
+        match result {
+            Ok(()) => {
+                if let Err(err) = store
+                    .mark_delivered(&backend.tag, &queue.channel_id, seq)
+                    .await
+                {
+                    warn!(
+                        "failed to mark message {} delivered for {}:{}: {}",
+                        seq, backend.tag, queue.channel_id, err
+                    );
+                }
+            }
+            Err(err) => error!(
+                "failed to deliver message to {} on {}: {}",
+                queue.channel_id, backend.tag, err
+            ),
+        }
+    }
+}
+
+impl Client {
+    // Keeps a single backend's reader alive indefinitely: whenever the
+    // stream ends or fails to (re)connect, this rebuilds the backend's
+    // seabird::Client and retries with exponential backoff instead of
+    // letting the failure tear down the whole process. proxied_channels and
+    // the per-target spools are untouched across reconnects, so config and
+    // anything already queued survive the blip.
+    async fn run_supervised_reader(&self, tag: &str) {
+        let mut backoff = Backoff::new();
+
+        loop {
+            let connected_at = Instant::now();
+
+            if let Err(err) = self.run_reader(tag).await {
+                error!("backend {} reader exited: {}", tag, err);
+            }
+
+            if connected_at.elapsed() >= HEALTHY_CONNECTION_THRESHOLD {
+                backoff.reset();
+            }
+
+            warn!("reconnecting to backend {}...", tag);
+            backoff.wait().await;
+
+            match self.reconnect_backend(tag).await {
+                Ok(()) => info!("reconnected to backend {}", tag),
+                Err(err) => error!("failed to reconnect to backend {}: {}", tag, err),
+            }
+        }
+    }
+
+    // Rebuilds the underlying seabird::Client for a backend in place, so the
+    // next run_reader attempt re-issues stream_events against a fresh
+    // connection.
+    async fn reconnect_backend(&self, tag: &str) -> Result<()> {
+        let backend = self
+            .backends
+            .get(tag)
+            .ok_or_else(|| format_err!("unknown backend tag {}", tag))?;
+
+        let fresh_client = seabird::Client::new(backend.config.clone()).await?;
+        *backend.inner.write().await = fresh_client;
+
+        self.backfill_backend(tag).await?;
+
+        Ok(())
+    }
+
+    async fn run_reader(&self, tag: &str) -> Result<()> {
+        let backend = self
+            .backends
+            .get(tag)
+            .ok_or_else(|| format_err!("unknown backend tag {}", tag))?;
+
+        debug!("Getting stream for backend {}", tag);
+
+        let mut client = backend.inner.read().await.clone();
+
+        let mut stream = client
             .inner_mut_ref()
             .stream_events(proto::StreamEventsRequest {
-                commands: HashMap::new(),
+                commands: management_commands(),
             })
             .await?
             .into_inner();
 
-        debug!("Got stream");
+        debug!("Got stream for backend {}", tag);
 
         while let Some(event) = stream.next().await.transpose()? {
-            info!("<-- {:?}", event);
+            info!("<-- [{}] {:?}", tag, event);
 
-            match self.handle_event(&mut queue, event).await {
+            match self.handle_event(tag, event).await {
                 Err(err) => error!("failed to handle event: {}", err),
                 _ => {}
             }
         }
 
-        Err(format_err!("run_reader exited early"))
+        Err(format_err!("run_reader for backend {} exited early", tag))
     }
 
-    async fn run_writer(&self, mut queue: mpsc::Receiver<OutgoingMessage>) -> Result<()> {
-        loop {
-            match queue.recv().await {
-                Some(OutgoingMessage::Action(action)) => {
-                    let mut inner = self.inner.lock().await;
-                    debug!("Performing action {} on {}", action.text, action.channel_id);
-                    inner
-                        .perform_action(action.channel_id, action.text, None)
-                        .await?;
-                }
-                Some(OutgoingMessage::Message(message)) => {
-                    let mut inner = self.inner.lock().await;
-                    debug!("Sending message {} to {}", message.text, message.channel_id);
-                    inner
-                        .send_message(message.channel_id, message.text, None)
-                        .await?;
-                }
-                None => return Err(format_err!("run_writer exited early")),
-            }
-        }
-    }
-
-    async fn handle_event(
-        &self,
-        queue: &mut mpsc::Sender<OutgoingMessage>,
-        event: SeabirdEvent,
-    ) -> Result<()> {
+    async fn handle_event(&self, backend_tag: &str, event: SeabirdEvent) -> Result<()> {
         // If the plugin requested for this event to not be proxied, we need to
         // skip it.
         if event
@@ -165,8 +839,14 @@ impl Client {
                     .ok_or_else(|| format_err!("event missing user"))?;
                 let text = action.text;
 
-                self.send_msg(queue, source.channel_id, &tags, |prefix, suffix| {
-                    format!("* {}{}{} {}", prefix, user.display_name, suffix, text)
+                self.send_msg(backend_tag, source.channel_id, &tags, |target| {
+                    render_template(
+                        target.action_format(),
+                        &[
+                            ("user", &target.display_name(&user.display_name)),
+                            ("text", &text),
+                        ],
+                    )
                 })
                 .await?;
             }
@@ -181,8 +861,14 @@ impl Client {
                     .ok_or_else(|| format_err!("event missing user"))?;
                 let text = message.text;
 
-                self.send_msg(queue, source.channel_id, &tags, |prefix, suffix| {
-                    format!("{}{}{}: {}", prefix, user.display_name, suffix, text)
+                self.send_msg(backend_tag, source.channel_id, &tags, |target| {
+                    render_template(
+                        target.message_format(),
+                        &[
+                            ("user", &target.display_name(&user.display_name)),
+                            ("text", &text),
+                        ],
+                    )
                 })
                 .await?;
             }
@@ -199,21 +885,26 @@ impl Client {
                 let cmd = command.command;
                 let arg = command.arg;
 
-                // TODO: maybe pull command prefix from some other API?
-                if arg != "" {
-                    self.send_msg(queue, source.channel_id, &tags, |prefix, suffix| {
-                        format!(
-                            "{}{}{}: !{} {}",
-                            prefix, user.display_name, suffix, cmd, arg
-                        )
-                    })
-                    .await?;
-                } else {
-                    self.send_msg(queue, source.channel_id, &tags, |prefix, suffix| {
-                        format!("{}{}{}: !{}", prefix, user.display_name, suffix, cmd)
-                    })
-                    .await?;
+                if management_commands().contains_key(&cmd) {
+                    self.handle_management_command(backend_tag, source.channel_id, &user.id, &cmd, &arg)
+                        .await?;
+                    return Ok(());
                 }
+
+                // TODO: maybe pull command prefix from some other API?
+                self.send_msg(backend_tag, source.channel_id, &tags, |target| {
+                    render_template(
+                        target.command_format(),
+                        &[
+                            ("user", &target.display_name(&user.display_name)),
+                            ("command", &cmd),
+                            ("text", &arg),
+                        ],
+                    )
+                    .trim_end()
+                    .to_string()
+                })
+                .await?;
             }
             SeabirdEventInner::Mention(mention) => {
                 info!("Mention: {:?}", mention);
@@ -228,10 +919,14 @@ impl Client {
 
                 let nick = self.get_current_nick().await?;
 
-                self.send_msg(queue, source.channel_id, &tags, |prefix, suffix| {
-                    format!(
-                        "{}{}{}: {}: {}",
-                        prefix, user.display_name, suffix, nick, text
+                self.send_msg(backend_tag, source.channel_id, &tags, |target| {
+                    render_template(
+                        target.mention_format(),
+                        &[
+                            ("user", &target.display_name(&user.display_name)),
+                            ("nick", &nick),
+                            ("text", &text),
+                        ],
                     )
                 })
                 .await?;
@@ -239,7 +934,7 @@ impl Client {
 
             // Seabird-sent events
             SeabirdEventInner::SendMessage(message) => {
-                if message.sender == self.config.tag {
+                if message.sender == backend_tag {
                     debug!(
                         "Skipping Send Message from {}: {:?}",
                         message.sender, message
@@ -249,11 +944,11 @@ impl Client {
 
                 info!("Send Message: {:?}", message);
 
-                self.send_raw_msg(queue, message.channel_id, &tags, message.text)
+                self.send_raw_msg(backend_tag, message.channel_id, &tags, message.text)
                     .await?;
             }
             SeabirdEventInner::PerformAction(action) => {
-                if action.sender == self.config.tag {
+                if action.sender == backend_tag {
                     debug!(
                         "Skipping Perform Action from {}: {:?}",
                         action.sender, action
@@ -263,7 +958,7 @@ impl Client {
 
                 info!("Perform Action: {:?}", action);
 
-                self.perform_raw_action(queue, action.channel_id, &tags, action.text)
+                self.perform_raw_action(backend_tag, action.channel_id, &tags, action.text)
                     .await?;
             }
 
@@ -282,33 +977,158 @@ impl Client {
         Ok("seabird".to_string())
     }
 
+    // Dispatches one of the proxy-* management commands, replying in the
+    // requesting channel. These mutate the same proxied_channels map that a
+    // SIGHUP config reload writes to.
+    async fn handle_management_command(
+        &self,
+        backend_tag: &str,
+        channel_id: String,
+        user_id: &str,
+        cmd: &str,
+        arg: &str,
+    ) -> Result<()> {
+        if !self.admins.read().await.contains(user_id) {
+            self.queue_message(
+                backend_tag,
+                channel_id,
+                "you are not authorized to manage proxying".to_string(),
+            )
+            .await?;
+            return Ok(());
+        }
+
+        let reply = match cmd {
+            "proxy-list" => self.proxy_list().await,
+            "proxy-status" => self.proxy_status().await,
+            "proxy-add" => self.proxy_add(backend_tag, arg).await,
+            "proxy-remove" => self.proxy_remove(backend_tag, arg).await,
+            _ => format!("unknown management command: {}", cmd),
+        };
+
+        self.queue_message(backend_tag, channel_id, reply).await?;
+
+        Ok(())
+    }
+
+    async fn proxy_list(&self) -> String {
+        let guard = self.proxied_channels.read().await;
+
+        if guard.is_empty() {
+            return "no channels are currently proxied".to_string();
+        }
+
+        let mut lines = Vec::new();
+        for ((source_backend, source_id), targets) in guard.iter() {
+            let targets = targets
+                .iter()
+                .map(|t| format!("{}:{}", t.backend_tag, t.id))
+                .collect::<Vec<_>>()
+                .join(", ");
+            lines.push(format!("{}:{} -> {}", source_backend, source_id, targets));
+        }
+
+        lines.join(" | ")
+    }
+
+    async fn proxy_status(&self) -> String {
+        let guard = self.proxied_channels.read().await;
+        let target_count: usize = guard.values().map(Vec::len).sum();
+
+        format!(
+            "{} backend(s), {} proxied source channel(s), {} total target(s)",
+            self.backends.len(),
+            guard.len(),
+            target_count
+        )
+    }
+
+    async fn proxy_add(&self, requesting_backend: &str, arg: &str) -> String {
+        let mut parts = arg.split_whitespace();
+        let (source_raw, target_raw) = match (parts.next(), parts.next()) {
+            (Some(source), Some(target)) => (source, target),
+            _ => return "usage: proxy-add <source> <target>".to_string(),
+        };
+
+        let source = parse_channel_ref(requesting_backend, source_raw);
+        let target = parse_channel_ref(requesting_backend, target_raw);
+
+        let mut guard = self.proxied_channels.write().await;
+        guard
+            .entry(source.clone())
+            .or_insert_with(Vec::new)
+            .push(ChannelTarget::new(target.0.clone(), target.1.clone(), None, None));
+
+        format!(
+            "now proxying {}:{} -> {}:{}",
+            source.0, source.1, target.0, target.1
+        )
+    }
+
+    async fn proxy_remove(&self, requesting_backend: &str, arg: &str) -> String {
+        let mut parts = arg.split_whitespace();
+        let (source_raw, target_raw) = match (parts.next(), parts.next()) {
+            (Some(source), Some(target)) => (source, target),
+            _ => return "usage: proxy-remove <source> <target>".to_string(),
+        };
+
+        let source = parse_channel_ref(requesting_backend, source_raw);
+        let target = parse_channel_ref(requesting_backend, target_raw);
+
+        let mut guard = self.proxied_channels.write().await;
+        let removed = if let Some(targets) = guard.get_mut(&source) {
+            let before = targets.len();
+            targets.retain(|t| (t.backend_tag.as_str(), t.id.as_str()) != (target.0.as_str(), target.1.as_str()));
+            let removed = before != targets.len();
+
+            if targets.is_empty() {
+                guard.remove(&source);
+            }
+
+            removed
+        } else {
+            false
+        };
+
+        if removed {
+            format!("stopped proxying {}:{} -> {}:{}", source.0, source.1, target.0, target.1)
+        } else {
+            format!("no such proxy mapping {}:{} -> {}:{}", source.0, source.1, target.0, target.1)
+        }
+    }
+
     async fn send_msg<T>(
         &self,
-        queue: &mut mpsc::Sender<OutgoingMessage>,
-        source: String,
+        source_backend: &str,
+        source_channel: String,
         tags: &HashMap<String, String>,
         cb: T,
     ) -> Result<()>
     where
-        T: Fn(&str, &str) -> String,
+        T: Fn(&ChannelTarget) -> String,
     {
-        if let Some(channels) = self.proxied_channels.read().await.get(&source) {
-            for channel in channels.iter() {
-                let text = cb(
-                    channel.user_prefix.as_deref().unwrap_or(""),
-                    channel.user_suffix.as_deref().unwrap_or(""),
-                );
+        let key = (source_backend.to_string(), source_channel);
 
-                debug!("Queuing message {} to {}", text, channel.id);
+        let targets: Vec<_> = match self.proxied_channels.read().await.get(&key) {
+            Some(channels) => channels
+                .iter()
+                .map(|c| (c.backend_tag.clone(), c.id.clone(), c.rate_limit, cb(c)))
+                .collect(),
+            None => return Ok(()),
+        };
 
-                queue
-                    .send(OutgoingMessage::Message(proto::SendMessageRequest {
-                        channel_id: channel.id.clone(),
-                        text,
-                        tags: tags.clone(),
-                    }))
-                    .await?;
-            }
+        for (backend_tag, channel_id, rate_limit, text) in targets {
+            debug!("Queuing message {} to {}", text, channel_id);
+
+            self.enqueue(
+                &backend_tag,
+                channel_id,
+                rate_limit,
+                MessageKind::Message,
+                text,
+                tags.clone(),
+            )
+            .await?;
         }
 
         Ok(())
@@ -316,23 +1136,33 @@ impl Client {
 
     async fn send_raw_msg(
         &self,
-        queue: &mut mpsc::Sender<OutgoingMessage>,
-        source: String,
+        source_backend: &str,
+        source_channel: String,
         tags: &HashMap<String, String>,
         text: String,
     ) -> Result<()> {
-        if let Some(channels) = self.proxied_channels.read().await.get(&source) {
-            for channel in channels.iter() {
-                debug!("Queuing message {} to {}", text, channel.id);
-
-                queue
-                    .send(OutgoingMessage::Message(proto::SendMessageRequest {
-                        channel_id: channel.id.clone(),
-                        text: text.clone(),
-                        tags: tags.clone(),
-                    }))
-                    .await?;
-            }
+        let key = (source_backend.to_string(), source_channel);
+
+        let targets: Vec<_> = match self.proxied_channels.read().await.get(&key) {
+            Some(channels) => channels
+                .iter()
+                .map(|c| (c.backend_tag.clone(), c.id.clone(), c.rate_limit))
+                .collect(),
+            None => return Ok(()),
+        };
+
+        for (backend_tag, channel_id, rate_limit) in targets {
+            debug!("Queuing message {} to {}", text, channel_id);
+
+            self.enqueue(
+                &backend_tag,
+                channel_id,
+                rate_limit,
+                MessageKind::Message,
+                text.clone(),
+                tags.clone(),
+            )
+            .await?;
         }
 
         Ok(())
@@ -340,25 +1170,134 @@ impl Client {
 
     async fn perform_raw_action(
         &self,
-        queue: &mut mpsc::Sender<OutgoingMessage>,
-        source: String,
+        source_backend: &str,
+        source_channel: String,
         tags: &HashMap<String, String>,
         text: String,
     ) -> Result<()> {
-        if let Some(channels) = self.proxied_channels.read().await.get(&source) {
-            for channel in channels.iter() {
-                debug!("Queuing action {} on {}", text, channel.id);
-
-                queue
-                    .send(OutgoingMessage::Action(proto::PerformActionRequest {
-                        channel_id: channel.id.clone(),
-                        text: text.clone(),
-                        tags: tags.clone(),
-                    }))
-                    .await?;
-            }
+        let key = (source_backend.to_string(), source_channel);
+
+        let targets: Vec<_> = match self.proxied_channels.read().await.get(&key) {
+            Some(channels) => channels
+                .iter()
+                .map(|c| (c.backend_tag.clone(), c.id.clone(), c.rate_limit))
+                .collect(),
+            None => return Ok(()),
+        };
+
+        for (backend_tag, channel_id, rate_limit) in targets {
+            debug!("Queuing action {} on {}", text, channel_id);
+
+            self.enqueue(
+                &backend_tag,
+                channel_id,
+                rate_limit,
+                MessageKind::Action,
+                text.clone(),
+                tags.clone(),
+            )
+            .await?;
         }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(seq: u64, text: &str) -> OutgoingMessage {
+        OutgoingMessage::Message {
+            seq,
+            request: proto::SendMessageRequest {
+                channel_id: "#general".to_string(),
+                text: text.to_string(),
+                tags: HashMap::new(),
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn enqueued_seqs_only_reflects_messages_still_in_the_queue() {
+        let queue = TargetQueue::new("irc".to_string(), "#general".to_string());
+
+        queue.push(message(1, "one")).await;
+        queue.push(message(2, "two")).await;
+        assert_eq!(
+            queue.enqueued_seqs().await,
+            [1, 2].into_iter().collect::<HashSet<_>>()
+        );
+
+        // Popping (e.g. a spool task picking the message up to attempt
+        // delivery) drops it from this set even if the send later fails,
+        // since that's exactly the case replay_backlog needs to catch.
+        let popped = queue.pop().await;
+        assert_eq!(popped.seq(), 1);
+        assert_eq!(
+            queue.enqueued_seqs().await,
+            [2].into_iter().collect::<HashSet<_>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn token_bucket_refills_over_time() {
+        let mut bucket = TokenBucket::new(RateLimitConfig {
+            burst: 1.0,
+            refill_per_sec: 1000.0,
+        });
+
+        // The initial burst token is available immediately.
+        let start = Instant::now();
+        bucket.acquire().await;
+        assert!(start.elapsed() < Duration::from_millis(50));
+
+        // The next one has to wait for a refill.
+        let start = Instant::now();
+        bucket.acquire().await;
+        assert!(start.elapsed() >= Duration::from_millis(1));
+    }
+
+    #[test]
+    fn render_template_substitutes_known_placeholders() {
+        let out = render_template("{user}: {text}", &[("user", "alice"), ("text", "hi")]);
+        assert_eq!(out, "alice: hi");
+    }
+
+    #[test]
+    fn render_template_leaves_unknown_placeholders_untouched() {
+        let out = render_template("{user} says {mystery}", &[("user", "bob")]);
+        assert_eq!(out, "bob says {mystery}");
+    }
+
+    #[test]
+    fn render_template_does_not_rescan_substituted_values() {
+        // A display name that happens to look like a later placeholder must
+        // not be expanded again - only tokens from the original template are
+        // substitution sites.
+        let out = render_template("{user}: {text}", &[("user", "{text}"), ("text", "hi")]);
+        assert_eq!(out, "{text}: hi");
+    }
+
+    #[test]
+    fn render_template_handles_unterminated_braces() {
+        let out = render_template("{user", &[("user", "alice")]);
+        assert_eq!(out, "{user");
+    }
+
+    #[test]
+    fn parse_channel_ref_uses_default_backend_without_prefix() {
+        assert_eq!(
+            parse_channel_ref("irc", "#general"),
+            ("irc".to_string(), "#general".to_string())
+        );
+    }
+
+    #[test]
+    fn parse_channel_ref_honors_explicit_backend_prefix() {
+        assert_eq!(
+            parse_channel_ref("irc", "discord:#general"),
+            ("discord".to_string(), "#general".to_string())
+        );
+    }
+}
@@ -0,0 +1,185 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use feed_rs::parser as feed_parser;
+
+use crate::client::Client;
+use crate::prelude::*;
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct FeedConfig {
+    pub url: String,
+    pub backend_tag: String,
+    pub channel_id: String,
+    pub poll_interval_secs: u64,
+    #[serde(default = "default_format")]
+    pub format: String,
+}
+
+fn default_format() -> String {
+    "{title} - {link}".to_string()
+}
+
+// Tracks which entries have already been announced for a single feed, so a
+// restart doesn't re-announce everything. Persisted to a small file next to
+// the rest of the plugin's state.
+struct SeenEntries {
+    path: PathBuf,
+    ids: HashSet<String>,
+}
+
+impl SeenEntries {
+    async fn load(state_dir: &str, feed_url: &str) -> Self {
+        let path = PathBuf::from(state_dir).join(format!("{:x}.seen", stable_hash_hex(feed_url)));
+
+        let ids = match tokio::fs::read_to_string(&path).await {
+            Ok(contents) => contents.lines().map(str::to_string).collect(),
+            Err(_) => HashSet::new(),
+        };
+
+        SeenEntries { path, ids }
+    }
+
+    fn contains(&self, id: &str) -> bool {
+        self.ids.contains(id)
+    }
+
+    async fn mark_seen(&mut self, id: String) -> Result<()> {
+        self.ids.insert(id);
+
+        let contents = self.ids.iter().cloned().collect::<Vec<_>>().join("\n");
+        tokio::fs::write(&self.path, contents).await?;
+
+        Ok(())
+    }
+}
+
+fn stable_hash_hex(input: &str) -> u64 {
+    // We don't need cryptographic properties here, just a stable, filesystem
+    // safe name for the per-feed seen-entries file.
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = DefaultHasher::new();
+    input.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn render(format: &str, title: &str, link: &str) -> String {
+    format.replace("{title}", title).replace("{link}", link)
+}
+
+// Polls a single feed on its configured interval, announcing any entries
+// that haven't previously been seen into its configured channel.
+async fn poll_feed(client: Arc<Client>, config: FeedConfig, state_dir: String) -> Result<()> {
+    if config.poll_interval_secs == 0 {
+        return Err(format_err!(
+            "feed {} has poll_interval_secs = 0, must be greater than zero",
+            config.url
+        ));
+    }
+
+    let mut seen = SeenEntries::load(&state_dir, &config.url).await;
+    let http_client = reqwest::Client::new();
+    let mut etag: Option<String> = None;
+    let mut last_modified: Option<String> = None;
+
+    let mut interval = tokio::time::interval(Duration::from_secs(config.poll_interval_secs));
+
+    loop {
+        interval.tick().await;
+
+        let mut request = http_client.get(&config.url);
+        if let Some(etag) = &etag {
+            request = request.header(reqwest::header::IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &last_modified {
+            request = request.header(reqwest::header::IF_MODIFIED_SINCE, last_modified);
+        }
+
+        let response = match request.send().await {
+            Ok(response) => response,
+            Err(err) => {
+                warn!("failed to poll feed {}: {}", config.url, err);
+                continue;
+            }
+        };
+
+        if response.status() == reqwest::StatusCode::NOT_MODIFIED {
+            debug!("feed {} not modified", config.url);
+            continue;
+        }
+
+        if let Some(value) = response.headers().get(reqwest::header::ETAG) {
+            etag = value.to_str().ok().map(str::to_string);
+        }
+        if let Some(value) = response.headers().get(reqwest::header::LAST_MODIFIED) {
+            last_modified = value.to_str().ok().map(str::to_string);
+        }
+
+        let body = match response.bytes().await {
+            Ok(body) => body,
+            Err(err) => {
+                warn!("failed to read feed {}: {}", config.url, err);
+                continue;
+            }
+        };
+
+        let feed = match feed_parser::parse(&body[..]) {
+            Ok(feed) => feed,
+            Err(err) => {
+                warn!("failed to parse feed {}: {}", config.url, err);
+                continue;
+            }
+        };
+
+        for entry in feed.entries {
+            if seen.contains(&entry.id) {
+                continue;
+            }
+
+            let title = entry
+                .title
+                .map(|t| t.content)
+                .unwrap_or_else(|| "(untitled)".to_string());
+            let link = entry
+                .links
+                .first()
+                .map(|l| l.href.clone())
+                .unwrap_or_default();
+
+            let text = render(&config.format, &title, &link);
+
+            if let Err(err) = client
+                .queue_message(&config.backend_tag, config.channel_id.clone(), text)
+                .await
+            {
+                error!("failed to announce feed entry for {}: {}", config.url, err);
+                continue;
+            }
+
+            if let Err(err) = seen.mark_seen(entry.id).await {
+                warn!("failed to persist seen feed entry for {}: {}", config.url, err);
+            }
+        }
+    }
+}
+
+// Spawns one detached polling task per configured feed; no handles are
+// returned. A failure polling one feed only logs and retries on the next
+// interval rather than tearing down the others; a feed with a zero poll
+// interval logs an error and its task exits.
+pub fn spawn_pollers(client: Arc<Client>, feeds: Vec<FeedConfig>, state_dir: String) {
+    for config in feeds {
+        let client = client.clone();
+        let state_dir = state_dir.clone();
+
+        tokio::spawn(async move {
+            let url = config.url.clone();
+            if let Err(err) = poll_feed(client, config, state_dir).await {
+                error!("feed poller for {} exited: {}", url, err);
+            }
+        });
+    }
+}
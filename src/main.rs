@@ -1,11 +1,13 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashSet};
 
 use tokio::fs::File;
 use tokio::io::AsyncReadExt;
 use tokio::signal::unix::{signal, SignalKind};
 
 mod client;
+mod feed;
 mod prelude;
+mod store;
 
 use crate::prelude::*;
 
@@ -26,38 +28,106 @@ pub mod proto {
     pub use self::seabird::*;
 }
 
+#[derive(serde::Deserialize)]
+struct BackendConfig {
+    tag: String,
+    url: String,
+    token: String,
+}
+
 #[derive(serde::Deserialize)]
 struct ProxiedChannel {
+    source_backend: String,
     source: String,
+    target_backend: String,
     target: String,
+    #[serde(default)]
+    user_prefix: Option<String>,
     user_suffix: Option<String>,
+    #[serde(default)]
+    message_format: Option<String>,
+    #[serde(default)]
+    action_format: Option<String>,
+    #[serde(default)]
+    mention_format: Option<String>,
+    #[serde(default)]
+    command_format: Option<String>,
+    #[serde(default)]
+    burst: Option<f64>,
+    #[serde(default)]
+    refill_per_sec: Option<f64>,
 }
 
 #[derive(serde::Deserialize)]
 struct ConfigFile {
+    backends: Vec<BackendConfig>,
     proxied_channels: Vec<ProxiedChannel>,
+    #[serde(default)]
+    feeds: Vec<feed::FeedConfig>,
+    #[serde(default)]
+    admins: Vec<String>,
 }
 
-async fn read_config(filename: &str) -> Result<BTreeMap<String, Vec<client::ChannelTarget>>> {
+struct Config {
+    backends: Vec<client::ClientConfig>,
+    proxied_channels: BTreeMap<(String, String), Vec<client::ChannelTarget>>,
+    feeds: Vec<feed::FeedConfig>,
+    admins: HashSet<String>,
+}
+
+async fn read_config(filename: &str) -> Result<Config> {
     let mut buf = String::new();
     let mut file = File::open(filename).await?;
 
     file.read_to_string(&mut buf).await?;
 
-    let data: ConfigFile = serde_json::from_str(&buf)?;
+    let data: ConfigFile = if filename.ends_with(".toml") {
+        toml::from_str(&buf)?
+    } else {
+        serde_json::from_str(&buf)?
+    };
+
+    let backends = data
+        .backends
+        .into_iter()
+        .map(|backend| client::ClientConfig::new(backend.url, backend.token, backend.tag))
+        .collect();
 
-    let mut out = BTreeMap::new();
+    let mut proxied_channels = BTreeMap::new();
 
     for channel in data.proxied_channels.into_iter() {
-        out.entry(channel.source)
+        let default_rate_limit = client::RateLimitConfig::default();
+        let rate_limit = client::RateLimitConfig {
+            burst: channel.burst.unwrap_or(default_rate_limit.burst),
+            refill_per_sec: channel
+                .refill_per_sec
+                .unwrap_or(default_rate_limit.refill_per_sec),
+        };
+
+        proxied_channels
+            .entry((channel.source_backend, channel.source))
             .or_insert_with(Vec::new)
-            .push(client::ChannelTarget::new(
-                channel.target,
-                channel.user_suffix,
-            ));
+            .push(
+                client::ChannelTarget::new(
+                    channel.target_backend,
+                    channel.target,
+                    channel.user_prefix,
+                    channel.user_suffix,
+                )
+                .with_message_format(channel.message_format)
+                .with_action_format(channel.action_format)
+                .with_mention_format(channel.mention_format)
+                .with_command_format(channel.command_format)
+                .with_rate_limit(rate_limit),
+            );
     }
 
-    Ok(out)
+    Ok(Config {
+        backends,
+        proxied_channels,
+        feeds: data.feeds,
+        admins: data.admins.into_iter().collect(),
+    })
 }
 
 #[tokio::main]
@@ -83,19 +153,22 @@ async fn main() -> error::Result<()> {
     let config_file = dotenv::var("PROXY_CONFIG_FILE")
         .context("Missing $PROXY_CONFIG_FILE. You must specify a config file for the plugin.")?;
 
-    let proxied_channels = read_config(&config_file).await?;
+    let config = read_config(&config_file).await?;
+
+    let message_store: Arc<dyn store::MessageStore> =
+        match dotenv::var("PROXY_STORE_DATABASE_URL") {
+            Ok(url) => Arc::new(store::PostgresStore::connect(&url).await?),
+            Err(_) => Arc::new(store::MemoryStore::new()),
+        };
 
-    // Load our config from command line arguments
-    let config = client::ClientConfig::new(
-        dotenv::var("SEABIRD_HOST")
-            .context("Missing $SEABIRD_HOST. You must specify a Seabird host.")?,
-        dotenv::var("SEABIRD_TOKEN")
-            .context("Missing $SEABIRD_TOKEN. You must specify a valid auth token.")?,
-    );
+    let client = client::Client::new(config.backends, message_store).await?;
 
-    let client = client::Client::new(config).await?;
+    client.set_proxied_channels(config.proxied_channels).await;
+    client.set_admins(config.admins).await;
+    client.backfill_all().await?;
 
-    client.set_proxied_channels(proxied_channels).await;
+    let feed_state_dir = dotenv::var("PROXY_FEED_STATE_DIR").unwrap_or_else(|_| ".".to_string());
+    feed::spawn_pollers(client.clone(), config.feeds, feed_state_dir);
 
     // Spawn our token reader task
     let mut signal_stream = signal(SignalKind::hangup())?;
@@ -106,9 +179,15 @@ async fn main() -> error::Result<()> {
 
             info!("got SIGHUP, attempting to reload config");
 
+            // Note: this only reloads which channels are proxied to each
+            // other and who can manage them; the set of backend connections
+            // and feed pollers are fixed at startup.
             match read_config(&config_file).await {
-                Ok(proxied_channels) => {
-                    config_client.set_proxied_channels(proxied_channels).await;
+                Ok(config) => {
+                    config_client
+                        .set_proxied_channels(config.proxied_channels)
+                        .await;
+                    config_client.set_admins(config.admins).await;
                     info!("reloaded config");
                 }
                 Err(err) => warn!("failed to reload config: {}", err),
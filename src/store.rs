@@ -0,0 +1,301 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use tokio::sync::Mutex;
+
+use crate::prelude::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageKind {
+    Message,
+    Action,
+}
+
+#[derive(Debug, Clone)]
+pub struct StoredMessage {
+    pub seq: u64,
+    pub kind: MessageKind,
+    pub text: String,
+    pub tags: HashMap<String, String>,
+}
+
+// Records every message proxied to a target before it's dispatched, so that
+// if a target never confirmed delivery (e.g. we lost the connection to its
+// backend mid-send), the backlog can be replayed once a spool for that
+// target starts back up rather than silently dropping it.
+#[async_trait::async_trait]
+pub trait MessageStore: Send + Sync + std::fmt::Debug {
+    async fn record(
+        &self,
+        backend_tag: &str,
+        channel_id: &str,
+        kind: MessageKind,
+        text: &str,
+        tags: &HashMap<String, String>,
+    ) -> Result<u64>;
+
+    async fn mark_delivered(&self, backend_tag: &str, channel_id: &str, seq: u64) -> Result<()>;
+
+    async fn backlog(&self, backend_tag: &str, channel_id: &str) -> Result<Vec<StoredMessage>>;
+}
+
+// An in-memory MessageStore. This is what we fall back to when no database
+// is configured, and it's also what tests should reach for instead of a
+// real database.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    next_seq: AtomicU64,
+    pending: Mutex<HashMap<(String, String), Vec<StoredMessage>>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        MemoryStore::default()
+    }
+}
+
+#[async_trait::async_trait]
+impl MessageStore for MemoryStore {
+    async fn record(
+        &self,
+        backend_tag: &str,
+        channel_id: &str,
+        kind: MessageKind,
+        text: &str,
+        tags: &HashMap<String, String>,
+    ) -> Result<u64> {
+        let seq = self.next_seq.fetch_add(1, Ordering::Relaxed) + 1;
+
+        self.pending
+            .lock()
+            .await
+            .entry((backend_tag.to_string(), channel_id.to_string()))
+            .or_insert_with(Vec::new)
+            .push(StoredMessage {
+                seq,
+                kind,
+                text: text.to_string(),
+                tags: tags.clone(),
+            });
+
+        Ok(seq)
+    }
+
+    async fn mark_delivered(&self, backend_tag: &str, channel_id: &str, seq: u64) -> Result<()> {
+        if let Some(pending) = self
+            .pending
+            .lock()
+            .await
+            .get_mut(&(backend_tag.to_string(), channel_id.to_string()))
+        {
+            pending.retain(|msg| msg.seq > seq);
+        }
+
+        Ok(())
+    }
+
+    async fn backlog(&self, backend_tag: &str, channel_id: &str) -> Result<Vec<StoredMessage>> {
+        Ok(self
+            .pending
+            .lock()
+            .await
+            .get(&(backend_tag.to_string(), channel_id.to_string()))
+            .cloned()
+            .unwrap_or_default())
+    }
+}
+
+// A MessageStore backed by a bb8-pooled Postgres connection, for deployments
+// where the proxy's message history needs to survive a full process
+// restart, not just a dropped stream.
+#[derive(Debug)]
+pub struct PostgresStore {
+    pool: bb8::Pool<bb8_postgres::PostgresConnectionManager<tokio_postgres::NoTls>>,
+}
+
+impl PostgresStore {
+    pub async fn connect(url: &str) -> Result<Self> {
+        let manager =
+            bb8_postgres::PostgresConnectionManager::new_from_stringlike(url, tokio_postgres::NoTls)?;
+        let pool = bb8::Pool::builder().build(manager).await?;
+
+        pool.get()
+            .await?
+            .batch_execute(
+                "CREATE TABLE IF NOT EXISTS proxied_messages (
+                    seq BIGSERIAL PRIMARY KEY,
+                    backend_tag TEXT NOT NULL,
+                    channel_id TEXT NOT NULL,
+                    kind TEXT NOT NULL,
+                    text TEXT NOT NULL,
+                    tags JSONB NOT NULL,
+                    delivered BOOLEAN NOT NULL DEFAULT FALSE,
+                    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+                )",
+            )
+            .await?;
+
+        Ok(PostgresStore { pool })
+    }
+}
+
+#[async_trait::async_trait]
+impl MessageStore for PostgresStore {
+    async fn record(
+        &self,
+        backend_tag: &str,
+        channel_id: &str,
+        kind: MessageKind,
+        text: &str,
+        tags: &HashMap<String, String>,
+    ) -> Result<u64> {
+        let conn = self.pool.get().await?;
+        let tags_json = serde_json::to_value(tags)?;
+
+        let row = conn
+            .query_one(
+                "INSERT INTO proxied_messages (backend_tag, channel_id, kind, text, tags)
+                 VALUES ($1, $2, $3, $4, $5)
+                 RETURNING seq",
+                &[&backend_tag, &channel_id, &kind_str(kind), &text, &tags_json],
+            )
+            .await?;
+
+        Ok(row.get::<_, i64>(0) as u64)
+    }
+
+    async fn mark_delivered(&self, backend_tag: &str, channel_id: &str, seq: u64) -> Result<()> {
+        let conn = self.pool.get().await?;
+
+        conn.execute(
+            "UPDATE proxied_messages SET delivered = TRUE
+             WHERE backend_tag = $1 AND channel_id = $2 AND seq <= $3",
+            &[&backend_tag, &channel_id, &(seq as i64)],
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn backlog(&self, backend_tag: &str, channel_id: &str) -> Result<Vec<StoredMessage>> {
+        let conn = self.pool.get().await?;
+
+        let rows = conn
+            .query(
+                "SELECT seq, kind, text, tags FROM proxied_messages
+                 WHERE backend_tag = $1 AND channel_id = $2 AND NOT delivered
+                 ORDER BY seq",
+                &[&backend_tag, &channel_id],
+            )
+            .await?;
+
+        rows.into_iter()
+            .map(|row| {
+                let kind = match row.get::<_, &str>(1) {
+                    "action" => MessageKind::Action,
+                    _ => MessageKind::Message,
+                };
+                let tags: HashMap<String, String> = serde_json::from_value(row.get(3))?;
+
+                Ok(StoredMessage {
+                    seq: row.get::<_, i64>(0) as u64,
+                    kind,
+                    text: row.get(2),
+                    tags,
+                })
+            })
+            .collect()
+    }
+}
+
+fn kind_str(kind: MessageKind) -> &'static str {
+    match kind {
+        MessageKind::Message => "message",
+        MessageKind::Action => "action",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn record_then_backlog_round_trips_in_order() {
+        let store = MemoryStore::new();
+
+        let seq1 = store
+            .record("irc", "#general", MessageKind::Message, "hello", &HashMap::new())
+            .await
+            .unwrap();
+        let seq2 = store
+            .record("irc", "#general", MessageKind::Action, "waves", &HashMap::new())
+            .await
+            .unwrap();
+        assert!(seq2 > seq1);
+
+        let backlog = store.backlog("irc", "#general").await.unwrap();
+        assert_eq!(backlog.len(), 2);
+        assert_eq!(backlog[0].seq, seq1);
+        assert_eq!(backlog[0].text, "hello");
+        assert_eq!(backlog[1].seq, seq2);
+        assert_eq!(backlog[1].kind, MessageKind::Action);
+    }
+
+    #[tokio::test]
+    async fn mark_delivered_drops_everything_up_to_and_including_seq() {
+        let store = MemoryStore::new();
+
+        store
+            .record("irc", "#general", MessageKind::Message, "one", &HashMap::new())
+            .await
+            .unwrap();
+        let seq2 = store
+            .record("irc", "#general", MessageKind::Message, "two", &HashMap::new())
+            .await
+            .unwrap();
+        let seq3 = store
+            .record("irc", "#general", MessageKind::Message, "three", &HashMap::new())
+            .await
+            .unwrap();
+
+        store.mark_delivered("irc", "#general", seq2).await.unwrap();
+
+        let backlog = store.backlog("irc", "#general").await.unwrap();
+        assert_eq!(backlog.len(), 1);
+        assert_eq!(backlog[0].seq, seq3);
+    }
+
+    #[tokio::test]
+    async fn backlog_is_scoped_per_backend_and_channel() {
+        let store = MemoryStore::new();
+
+        store
+            .record("irc", "#general", MessageKind::Message, "irc message", &HashMap::new())
+            .await
+            .unwrap();
+        store
+            .record(
+                "discord",
+                "#general",
+                MessageKind::Message,
+                "discord message",
+                &HashMap::new(),
+            )
+            .await
+            .unwrap();
+
+        let irc_backlog = store.backlog("irc", "#general").await.unwrap();
+        assert_eq!(irc_backlog.len(), 1);
+        assert_eq!(irc_backlog[0].text, "irc message");
+
+        let discord_backlog = store.backlog("discord", "#general").await.unwrap();
+        assert_eq!(discord_backlog.len(), 1);
+        assert_eq!(discord_backlog[0].text, "discord message");
+    }
+
+    #[tokio::test]
+    async fn backlog_is_empty_for_unknown_target() {
+        let store = MemoryStore::new();
+        assert!(store.backlog("irc", "#nowhere").await.unwrap().is_empty());
+    }
+}